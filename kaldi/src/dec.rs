@@ -0,0 +1,225 @@
+//! Typed decoder combinators over `Component`.
+//!
+//! In the spirit of netencode's `dec` module: small composable decoder
+//! values, each carrying its decoded type as an associated `Output`, that
+//! pull one attribute out of a `Component`'s raw `attributes` map, check its
+//! rank/dtype, and return a typed value or a descriptive `TractError`. This
+//! replaces ad-hoc `attributes.get("Foo").unwrap()` call sites with a
+//! reusable, testable surface.
+
+use std::sync::Arc;
+
+use tract_core::internal::*;
+
+use crate::model::Component;
+
+/// Decodes one piece of typed data out of a `Component`.
+pub trait ComponentDecoder {
+    type Output;
+    fn decode(&self, component: &Component) -> TractResult<Self::Output>;
+}
+
+/// Looks up `name` in `component.attributes` and checks it holds `dtype`,
+/// with no rank checking.
+pub fn field(name: &'static str, dtype: DatumType) -> FieldDecoder {
+    FieldDecoder { name, dtype }
+}
+
+pub struct FieldDecoder {
+    name: &'static str,
+    dtype: DatumType,
+}
+
+impl ComponentDecoder for FieldDecoder {
+    type Output = Arc<Tensor>;
+    fn decode(&self, component: &Component) -> TractResult<Arc<Tensor>> {
+        let t = component
+            .attributes
+            .get(self.name)
+            .cloned()
+            .ok_or_else(|| format!("{} missing attribute {}", component.klass, self.name))?;
+        if t.datum_type() != self.dtype {
+            return Err(format!(
+                "{} attribute {} expected {:?}, got {:?}",
+                component.klass,
+                self.name,
+                self.dtype,
+                t.datum_type()
+            )
+            .into());
+        }
+        Ok(t)
+    }
+}
+
+/// Looks up `name` and checks it's a rank-0 `f32` tensor.
+pub fn scalar_f32(name: &'static str) -> ScalarF32Decoder {
+    ScalarF32Decoder { name }
+}
+
+pub struct ScalarF32Decoder {
+    name: &'static str,
+}
+
+impl ComponentDecoder for ScalarF32Decoder {
+    type Output = f32;
+    fn decode(&self, component: &Component) -> TractResult<f32> {
+        let t = component
+            .attributes
+            .get(self.name)
+            .ok_or_else(|| format!("{} missing scalar attribute {}", component.klass, self.name))?;
+        if t.rank() != 0 {
+            return Err(format!(
+                "{} attribute {} expected a scalar, got rank {}",
+                component.klass,
+                self.name,
+                t.rank()
+            )
+            .into());
+        }
+        if t.datum_type() != DatumType::F32 {
+            return Err(format!(
+                "{} attribute {} expected F32, got {:?}",
+                component.klass,
+                self.name,
+                t.datum_type()
+            )
+            .into());
+        }
+        Ok(*t.to_scalar::<f32>()?)
+    }
+}
+
+/// Looks up `name` and checks it's a rank-2 tensor holding `dtype`.
+pub fn matrix(name: &'static str, dtype: DatumType) -> MatrixDecoder {
+    MatrixDecoder { name, dtype }
+}
+
+pub struct MatrixDecoder {
+    name: &'static str,
+    dtype: DatumType,
+}
+
+impl ComponentDecoder for MatrixDecoder {
+    type Output = Arc<Tensor>;
+    fn decode(&self, component: &Component) -> TractResult<Arc<Tensor>> {
+        let t = component
+            .attributes
+            .get(self.name)
+            .cloned()
+            .ok_or_else(|| format!("{} missing matrix attribute {}", component.klass, self.name))?;
+        if t.rank() != 2 {
+            return Err(format!(
+                "{} attribute {} expected a matrix, got rank {}",
+                component.klass,
+                self.name,
+                t.rank()
+            )
+            .into());
+        }
+        if t.datum_type() != self.dtype {
+            return Err(format!(
+                "{} attribute {} expected {:?}, got {:?}",
+                component.klass,
+                self.name,
+                self.dtype,
+                t.datum_type()
+            )
+            .into());
+        }
+        Ok(t)
+    }
+}
+
+/// Validates `component.klass` is one of `classes` before running `inner`.
+pub fn one_of<D: ComponentDecoder>(classes: &'static [&'static str], inner: D) -> OneOfDecoder<D> {
+    OneOfDecoder { classes, inner }
+}
+
+pub struct OneOfDecoder<D> {
+    classes: &'static [&'static str],
+    inner: D,
+}
+
+impl<D: ComponentDecoder> ComponentDecoder for OneOfDecoder<D> {
+    type Output = D::Output;
+    fn decode(&self, component: &Component) -> TractResult<D::Output> {
+        if !self.classes.contains(&component.klass.as_str()) {
+            return Err(format!(
+                "expected component class to be one of {:?}, got {}",
+                self.classes, component.klass
+            )
+            .into());
+        }
+        self.inner.decode(component)
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn fixed_affine() -> Component {
+        let mut attributes: HashMap<String, Arc<Tensor>> = HashMap::new();
+        attributes.insert(
+            "LinearParams".to_string(),
+            tensor2(&[[1.0f32, 2.0], [3.0, 4.0]]).into_arc_tensor(),
+        );
+        attributes.insert("MaxChange".to_string(), Tensor::from(0.75f32).into_arc_tensor());
+        Component { klass: "FixedAffineComponent".to_string(), attributes }
+    }
+
+    #[test]
+    fn test_field_found() {
+        let c = fixed_affine();
+        assert!(field("LinearParams", DatumType::F32).decode(&c).is_ok());
+    }
+
+    #[test]
+    fn test_field_missing() {
+        let c = fixed_affine();
+        let e = field("BiasParams", DatumType::F32).decode(&c).unwrap_err();
+        assert_eq!(e.to_string(), "FixedAffineComponent missing attribute BiasParams");
+    }
+
+    #[test]
+    fn test_field_wrong_dtype_is_a_descriptive_error() {
+        let c = fixed_affine();
+        let e = field("MaxChange", DatumType::I32).decode(&c).unwrap_err();
+        assert_eq!(e.to_string(), "FixedAffineComponent attribute MaxChange expected I32, got F32");
+    }
+
+    #[test]
+    fn test_matrix_missing_names_the_field() {
+        let c = fixed_affine();
+        let e = matrix("BiasParams", DatumType::F32).decode(&c).unwrap_err();
+        assert_eq!(e.to_string(), "FixedAffineComponent missing matrix attribute BiasParams");
+    }
+
+    #[test]
+    fn test_matrix_wrong_dtype_is_a_descriptive_error() {
+        let c = fixed_affine();
+        let e = matrix("LinearParams", DatumType::F64).decode(&c).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "FixedAffineComponent attribute LinearParams expected F64, got F32"
+        );
+    }
+
+    #[test]
+    fn test_scalar_f32() {
+        let c = fixed_affine();
+        assert_eq!(scalar_f32("MaxChange").decode(&c).unwrap(), 0.75f32);
+    }
+
+    #[test]
+    fn test_one_of_rejects_wrong_class() {
+        let c = fixed_affine();
+        let e = one_of(&["AffineComponent"], field("LinearParams", DatumType::F32))
+            .decode(&c)
+            .unwrap_err();
+        assert!(e.to_string().contains("FixedAffineComponent"));
+    }
+}
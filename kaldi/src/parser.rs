@@ -3,35 +3,121 @@ use tract_core::internal::*;
 use nom::IResult;
 use nom::{
     bytes::complete::*, character::complete::*, combinator::*, multi::separated_list,
-    number::complete::float, sequence::*,
+    number::complete::{double, float},
+    sequence::*,
 };
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::model::{Component, KaldiProtoModel};
 
+mod binary;
 mod config_lines;
 mod descriptor;
 
+/// Which on-disk framing a Kaldi model is using for its numeric data.
+///
+/// Structural tokens (`<Nnet3>`, `<LinearParams>`, ...) are plain text in both
+/// modes; only `tensor`/`scalar`/`vector`/`matrix` need to know which one is in
+/// play.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Envelope {
+    Text,
+    Binary,
+}
+
+/// Kaldi's binary-format magic, written as the first two bytes of the file.
+const BINARY_MAGIC: &[u8] = b"\0B";
+
+/// What to do when a component name or attribute key shows up twice in the
+/// same model, rather than silently letting the later one win.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DuplicateKeyPolicy {
+    LastWins,
+    FirstWins,
+    Error,
+}
+
+impl Default for DuplicateKeyPolicy {
+    fn default() -> DuplicateKeyPolicy {
+        DuplicateKeyPolicy::LastWins
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ParseOptions {
+    pub duplicate_keys: DuplicateKeyPolicy,
+}
+
+/// Folds `entries` into a map, resolving repeated keys per `policy`. `kind`
+/// names what's being merged (`"component"`, `"attribute"`) for the error
+/// message.
+fn merge_with_policy<V>(
+    entries: Vec<(String, V)>,
+    policy: DuplicateKeyPolicy,
+    kind: &str,
+) -> TractResult<HashMap<String, V>> {
+    let mut map = HashMap::with_capacity(entries.len());
+    for (key, value) in entries {
+        if map.contains_key(&key) {
+            match policy {
+                DuplicateKeyPolicy::LastWins => {
+                    map.insert(key, value);
+                }
+                DuplicateKeyPolicy::FirstWins => (),
+                DuplicateKeyPolicy::Error => {
+                    return Err(format!("duplicate {} key {:?}", kind, key).into())
+                }
+            }
+        } else {
+            map.insert(key, value);
+        }
+    }
+    Ok(map)
+}
+
 pub fn nnet3(slice: &[u8]) -> TractResult<KaldiProtoModel> {
-    let (_, (config, components)) = parse_top_level(slice).map_err(|e| match e {
+    nnet3_with_options(slice, &ParseOptions::default())
+}
+
+pub fn nnet3_with_options(slice: &[u8], options: &ParseOptions) -> TractResult<KaldiProtoModel> {
+    let (slice, envelope) = if slice.starts_with(BINARY_MAGIC) {
+        (&slice[BINARY_MAGIC.len()..], Envelope::Binary)
+    } else {
+        (slice, Envelope::Text)
+    };
+    let (_, (config, raw_components)) = parse_top_level(envelope, slice).map_err(|e| match e {
         nom::Err::Error(err) => format!("Parsing kaldi enveloppe at: {:?}", err),
         e => format!("{:?}", e),
     })?;
     let config_lines = config_lines::parse_config(config)?;
+    let components = raw_components
+        .into_iter()
+        .map(|(name, klass, attr_pairs)| {
+            let attributes = merge_with_policy(attr_pairs, options.duplicate_keys, "attribute")?;
+            Ok((name, Component { klass, attributes }))
+        })
+        .collect::<TractResult<Vec<_>>>()?;
+    let components = merge_with_policy(components, options.duplicate_keys, "component")?;
     Ok(KaldiProtoModel { config_lines, components })
 }
 
-fn parse_top_level(i: &[u8]) -> IResult<&[u8], (&str, HashMap<String, Component>)> {
+#[allow(clippy::type_complexity)]
+fn parse_top_level(
+    envelope: Envelope,
+    i: &[u8],
+) -> IResult<&[u8], (&str, Vec<(String, String, Vec<(String, Arc<Tensor>)>)>)> {
     let (i, _) = open(i, "Nnet3")?;
     let (i, config_lines) = map_res(take_until("<NumComponents>"), std::str::from_utf8)(i)?;
     let (i, num_components) = num_components(i)?;
-    let mut components = HashMap::new();
+    let mut components = Vec::with_capacity(num_components);
     let mut i = i;
     for _ in 0..num_components {
-        let (new_i, (name, op)) = pair(component_name, component)(i)?;
+        let (new_i, (name, (klass, attributes))) =
+            pair(component_name, |i| component(envelope, i))(i)?;
         i = new_i;
-        components.insert(name.to_owned(), op);
+        components.push((name.to_owned(), klass, attributes));
     }
     let (i, _) = close(i, "Nnet3")?;
     Ok((i, (config_lines, components)))
@@ -43,14 +129,35 @@ fn num_components(i: &[u8]) -> IResult<&[u8], usize> {
     Ok((i, n as usize))
 }
 
-fn component(i: &[u8]) -> IResult<&[u8], Component> {
+/// Attribute names that are known to hold something other than an `f32`
+/// Kaldi `BaseFloat`. Everything not listed here defaults to
+/// `DatumType::F32`, matching the original float-only behavior.
+///
+/// `klass` additionally gates the float-vs-double attributes: components
+/// whose class name is prefixed `Double` (Kaldi's convention for the
+/// double-precision variant of a component, e.g. `DoubleAffineComponent`)
+/// carry `f64` matrices/vectors instead of `f32` ones.
+fn attribute_dtype(klass: &str, attr: &str) -> DatumType {
+    match attr {
+        "Dim" | "InputDim" | "OutputDim" | "Rank" | "BlockDim" | "NumRepeats" => DatumType::I32,
+        "IsGradient" => DatumType::Bool,
+        "LinearParams" | "BiasParams" | "Params" if klass.starts_with("Double") => DatumType::F64,
+        _ => DatumType::F32,
+    }
+}
+
+/// Parses one `<Klass> <Attr> value ... </Klass>` block, returning the class
+/// name and its attribute pairs in file order; duplicate keys are resolved
+/// later by the caller according to the configured `DuplicateKeyPolicy`.
+fn component(envelope: Envelope, i: &[u8]) -> IResult<&[u8], (String, Vec<(String, Arc<Tensor>)>)> {
     let (i, klass) = open_any(i)?;
-    let (i, attributes) = nom::multi::many0(map(pair(open_any, tensor), |(k, v)| {
-        (k.to_string(), v.into_arc_tensor())
-    }))(i)?;
-    let attributes = attributes.into_iter().collect();
+    let (i, attributes) = nom::multi::many0(|i| {
+        let (i, attr) = open_any(i)?;
+        let (i, value) = tensor(envelope, attribute_dtype(klass, attr), i)?;
+        Ok((i, (attr.to_string(), value.into_arc_tensor())))
+    })(i)?;
     let (i, _) = close(i, klass)?;
-    Ok((i, Component { klass: klass.to_string(), attributes }))
+    Ok((i, (klass.to_string(), attributes)))
 }
 
 fn component_name(i: &[u8]) -> IResult<&[u8], &str> {
@@ -79,37 +186,80 @@ pub fn name(i: &[u8]) -> IResult<&[u8], &str> {
     )(i)
 }
 
-pub fn tensor(i: &[u8]) -> IResult<&[u8], Tensor> {
-    nom::branch::alt((scalar, vector, matrix))(i)
+/// Parses one tensor, building it with `dtype`'s element type.
+///
+/// `dtype` is threaded down from `component`, which knows which attribute
+/// name/class it's reading and so what numeric type that attribute is
+/// supposed to hold; callers that don't care can pass `DatumType::F32` for
+/// the original, float-only behavior.
+pub fn tensor(envelope: Envelope, dtype: DatumType, i: &[u8]) -> IResult<&[u8], Tensor> {
+    match envelope {
+        Envelope::Text => nom::branch::alt((
+            move |i| scalar(dtype, i),
+            move |i| vector(dtype, i),
+            move |i| matrix(dtype, i),
+        ))(i),
+        Envelope::Binary => binary::binary_tensor(dtype, i),
+    }
 }
 
-pub fn matrix(i: &[u8]) -> IResult<&[u8], Tensor> {
+fn matrix_grid(i: &[u8]) -> IResult<&[u8], (usize, usize, Vec<f64>)> {
     let (i, v) = delimited(
         multispaced(tag("[")),
-        separated_list(spaced(tag("\n")), separated_list(space1, float)),
+        separated_list(spaced(tag("\n")), separated_list(space1, double)),
         multispaced(tag("]")),
     )(i)?;
     let lines = v.len();
-    let data: Vec<_> = v.into_iter().flat_map(|v| v.into_iter()).collect();
+    let data: Vec<f64> = v.into_iter().flat_map(|v| v.into_iter()).collect();
     let cols = data.len() / lines;
-    let t = tract_core::ndarray::Array1::from_vec(data);
-    let t = t.into_shape((lines, cols)).unwrap();
-    Ok((i, t.into_tensor()))
+    Ok((i, (lines, cols, data)))
+}
+
+fn array2<T: Datum>(lines: usize, cols: usize, data: Vec<T>) -> Tensor {
+    tract_core::ndarray::Array1::from_vec(data).into_shape((lines, cols)).unwrap().into_tensor()
+}
+
+pub fn matrix(dtype: DatumType, i: &[u8]) -> IResult<&[u8], Tensor> {
+    let (i, (lines, cols, data)) = matrix_grid(i)?;
+    let t = match dtype {
+        DatumType::F64 => array2(lines, cols, data),
+        DatumType::I32 => array2(lines, cols, data.into_iter().map(|v| v as i32).collect()),
+        DatumType::I64 => array2(lines, cols, data.into_iter().map(|v| v as i64).collect()),
+        DatumType::U8 => array2(lines, cols, data.into_iter().map(|v| v as u8).collect()),
+        _ => array2(lines, cols, data.into_iter().map(|v| v as f32).collect()),
+    };
+    Ok((i, t))
 }
 
-pub fn vector(i: &[u8]) -> IResult<&[u8], Tensor> {
-    map(delimited(spaced(tag("[")), separated_list(space1, float), spaced(tag("]"))), |t| {
-        tensor1(&*t)
-    })(i)
+pub fn vector(dtype: DatumType, i: &[u8]) -> IResult<&[u8], Tensor> {
+    let (i, data) = delimited(spaced(tag("[")), separated_list(space1, double), spaced(tag("]")))(i)?;
+    let t = match dtype {
+        DatumType::F64 => tensor1(&data),
+        DatumType::I32 => tensor1(&data.into_iter().map(|v| v as i32).collect::<Vec<_>>()),
+        DatumType::I64 => tensor1(&data.into_iter().map(|v| v as i64).collect::<Vec<_>>()),
+        DatumType::U8 => tensor1(&data.into_iter().map(|v| v as u8).collect::<Vec<_>>()),
+        _ => tensor1(&data.into_iter().map(|v| v as f32).collect::<Vec<_>>()),
+    };
+    Ok((i, t))
 }
 
-pub fn scalar(i: &[u8]) -> IResult<&[u8], Tensor> {
-    nom::branch::alt((
-        map(float, Tensor::from),
-        map(integer, Tensor::from),
-        map(tag("F"), |_| Tensor::from(false)),
-        map(tag("T"), |_| Tensor::from(true)),
-    ))(i)
+pub fn scalar(dtype: DatumType, i: &[u8]) -> IResult<&[u8], Tensor> {
+    match dtype {
+        DatumType::F64 => map(double, Tensor::from)(i),
+        DatumType::I32 => map(integer, Tensor::from)(i),
+        DatumType::I64 => map(integer, |v| Tensor::from(v as i64))(i),
+        DatumType::U8 => map(integer, |v| Tensor::from(v as u8))(i),
+        DatumType::Bool => nom::branch::alt((
+            map(tag("F"), |_| Tensor::from(false)),
+            map(tag("T"), |_| Tensor::from(true)),
+        ))(i),
+        _ => nom::branch::alt((
+            map(float, Tensor::from),
+            map(integer, Tensor::from),
+            map(tag("F"), |_| Tensor::from(false)),
+            map(tag("T"), |_| Tensor::from(true)),
+        ))(i),
+    }
 }
 
 pub fn integer(i: &[u8]) -> IResult<&[u8], i32> {
@@ -169,7 +319,7 @@ output-node name=output input=fixed1
     fn test_vector() {
         let slice = r#"[ 7.0 8.0 ]"#;
         assert_eq!(
-            tensor(slice.as_bytes()).unwrap().1,
+            tensor(Envelope::Text, DatumType::F32, slice.as_bytes()).unwrap().1,
             tract_core::internal::tensor1(&[7.0f32, 8.0])
         );
     }
@@ -180,14 +330,153 @@ output-node name=output input=fixed1
             1.0 2.0 3.0
             4.0 5.0 6.0 ]"#;
         assert_eq!(
-            tensor(slice.as_bytes()).unwrap().1,
+            tensor(Envelope::Text, DatumType::F32, slice.as_bytes()).unwrap().1,
             tract_core::internal::tensor2(&[[1.0f32, 2.0, 3.0], [4.0, 5.0, 6.0]])
         );
     }
 
+    #[test]
+    fn test_matrix_f64() {
+        let slice = r#"[
+            1.0 2.0 3.0
+            4.0 5.0 6.0 ]"#;
+        assert_eq!(
+            matrix(DatumType::F64, slice.as_bytes()).unwrap().1,
+            tract_core::internal::tensor2(&[[1.0f64, 2.0, 3.0], [4.0, 5.0, 6.0]])
+        );
+    }
+
+    #[test]
+    fn test_scalar_i64() {
+        let slice = r#"42"#;
+        assert_eq!(scalar(DatumType::I64, slice.as_bytes()).unwrap().1, Tensor::from(42i64));
+    }
+
     #[test]
     fn fixed_affine_40x10_T40_S3() {
         let slice = std::fs::read("test_cases/fixed_affine_40x10_T40_S3/model.raw.txt").unwrap();
         nnet3(&slice).unwrap();
     }
+
+    #[test]
+    fn test_nnet3_derives_double_matrix_dtype_from_klass() {
+        let slice = r#"<Nnet3>
+
+<NumComponents> 1
+<ComponentName> foo <DoubleAffineComponent> <LinearParams> [
+  1.0 2.0
+  3.0 4.0 ]
+</DoubleAffineComponent>
+</Nnet3>"#;
+        let model = nnet3(slice.as_bytes()).unwrap();
+        let linear = &model.components["foo"].attributes["LinearParams"];
+        assert_eq!(linear.datum_type(), DatumType::F64);
+        assert_eq!(**linear, tensor2(&[[1.0f64, 2.0], [3.0, 4.0]]));
+    }
+
+    #[test]
+    fn test_nnet3_derives_int_scalar_dtype_from_attribute_name() {
+        let slice = r#"<Nnet3>
+
+<NumComponents> 1
+<ComponentName> foo <FixedAffineComponent> <Dim> 5 </FixedAffineComponent>
+</Nnet3>"#;
+        let model = nnet3(slice.as_bytes()).unwrap();
+        let dim = &model.components["foo"].attributes["Dim"];
+        assert_eq!(dim.datum_type(), DatumType::I32);
+        assert_eq!(**dim, Tensor::from(5i32));
+    }
+
+    #[test]
+    fn test_nnet3_binary_int_scalar_is_not_bit_reinterpreted_as_float() {
+        let mut slice = BINARY_MAGIC.to_vec();
+        slice.extend_from_slice(b"<Nnet3>\n\n<NumComponents> 1\n");
+        slice.extend_from_slice(b"<ComponentName> foo <FixedAffineComponent> <Dim> ");
+        slice.push(4u8);
+        slice.extend_from_slice(&5i32.to_le_bytes());
+        slice.extend_from_slice(b" </FixedAffineComponent>\n</Nnet3>");
+        let model = nnet3(&slice).unwrap();
+        let dim = &model.components["foo"].attributes["Dim"];
+        assert_eq!(dim.datum_type(), DatumType::I32);
+        assert_eq!(**dim, Tensor::from(5i32));
+    }
+
+    #[test]
+    fn test_nnet3_binary_magic_is_detected() {
+        let mut slice = BINARY_MAGIC.to_vec();
+        slice.extend_from_slice(b"<Nnet3>");
+        // Stops at the first unparseable byte, but must not be mistaken for
+        // text and attempt to match `<` against the raw magic bytes.
+        assert!(nnet3(&slice).is_err());
+        // A complete envelope behind the magic parses; in text mode the
+        // leading `\0` would make `<Nnet3>` fail to match.
+        let mut slice = BINARY_MAGIC.to_vec();
+        slice.extend_from_slice(b"<Nnet3>\n\n<NumComponents> 0\n</Nnet3>");
+        assert!(nnet3(&slice).is_ok());
+    }
+
+    const DUPLICATE_COMPONENT_NAME: &str = r#"<Nnet3>
+
+input-node name=input dim=3
+
+<NumComponents> 2
+<ComponentName> foo <FixedAffineComponent> <BiasParams> [ 1.0 ]
+</FixedAffineComponent>
+<ComponentName> foo <FixedAffineComponent> <BiasParams> [ 2.0 ]
+</FixedAffineComponent>
+</Nnet3>"#;
+
+    #[test]
+    fn test_duplicate_component_last_wins_by_default() {
+        let model = nnet3(DUPLICATE_COMPONENT_NAME.as_bytes()).unwrap();
+        let bias = &model.components["foo"].attributes["BiasParams"];
+        assert_eq!(**bias, tensor1(&[2.0f32]));
+    }
+
+    #[test]
+    fn test_duplicate_component_first_wins() {
+        let options = ParseOptions { duplicate_keys: DuplicateKeyPolicy::FirstWins };
+        let model =
+            nnet3_with_options(DUPLICATE_COMPONENT_NAME.as_bytes(), &options).unwrap();
+        let bias = &model.components["foo"].attributes["BiasParams"];
+        assert_eq!(**bias, tensor1(&[1.0f32]));
+    }
+
+    #[test]
+    fn test_duplicate_component_error_names_the_key() {
+        let options = ParseOptions { duplicate_keys: DuplicateKeyPolicy::Error };
+        let e = nnet3_with_options(DUPLICATE_COMPONENT_NAME.as_bytes(), &options).unwrap_err();
+        assert_eq!(e.to_string(), "duplicate component key \"foo\"");
+    }
+
+    const DUPLICATE_ATTRIBUTE_KEY: &str = r#"<Nnet3>
+
+input-node name=input dim=3
+
+<NumComponents> 1
+<ComponentName> foo <FixedAffineComponent> <BiasParams> [ 1.0 ] <BiasParams> [ 2.0 ]
+</FixedAffineComponent>
+</Nnet3>"#;
+
+    #[test]
+    fn test_duplicate_attribute_last_wins_by_default() {
+        let model = nnet3(DUPLICATE_ATTRIBUTE_KEY.as_bytes()).unwrap();
+        let bias = &model.components["foo"].attributes["BiasParams"];
+        assert_eq!(**bias, tensor1(&[2.0f32]));
+    }
+
+    #[test]
+    fn test_duplicate_attribute_first_wins() {
+        let options = ParseOptions { duplicate_keys: DuplicateKeyPolicy::FirstWins };
+        let model = nnet3_with_options(DUPLICATE_ATTRIBUTE_KEY.as_bytes(), &options).unwrap();
+        let bias = &model.components["foo"].attributes["BiasParams"];
+        assert_eq!(**bias, tensor1(&[1.0f32]));
+    }
+
+    #[test]
+    fn test_duplicate_attribute_error_names_the_key() {
+        let options = ParseOptions { duplicate_keys: DuplicateKeyPolicy::Error };
+        let e = nnet3_with_options(DUPLICATE_ATTRIBUTE_KEY.as_bytes(), &options).unwrap_err();
+        assert_eq!(e.to_string(), "duplicate attribute key \"BiasParams\"");
+    }
 }
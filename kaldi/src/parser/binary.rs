@@ -0,0 +1,245 @@
+//! Binary-mode tensor parsing.
+//!
+//! Kaldi's binary format keeps structural tokens (`<Nnet3>`, `<LinearParams>`,
+//! `</FixedAffineComponent>`, ...) as plain text, but every number is written as
+//! a length-prefixed little-endian blob: a 1-byte "sizeof" marker (`\4` for a
+//! 4-byte value, `\8` for an 8-byte one) followed by that many raw bytes.
+//! Vectors and matrices are themselves introduced by a short un-bracketed token
+//! (`FV`/`DV`/`FM`/`DM`) naming the element width, followed by binary-encoded
+//! dimensions and then the packed row-major data.
+
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::combinator::map;
+use nom::multi::count;
+use nom::number::complete::{le_f32, le_f64, le_i32};
+use nom::sequence::preceded;
+use nom::IResult;
+
+use tract_core::internal::*;
+
+/// Consumes the 1-byte sizeof marker Kaldi prefixes every binary number with,
+/// checking it matches `width` (`4` for `f32`/`i32`, `8` for `f64`).
+fn sized(width: u8) -> impl Fn(&[u8]) -> IResult<&[u8], ()> {
+    move |i| map(tag(&[width][..]), |_| ())(i)
+}
+
+pub fn binary_i32(i: &[u8]) -> IResult<&[u8], i32> {
+    preceded(sized(4), le_i32)(i)
+}
+
+pub fn binary_f32(i: &[u8]) -> IResult<&[u8], f32> {
+    preceded(sized(4), le_f32)(i)
+}
+
+pub fn binary_f64(i: &[u8]) -> IResult<&[u8], f64> {
+    preceded(sized(8), le_f64)(i)
+}
+
+pub fn binary_bool(i: &[u8]) -> IResult<&[u8], bool> {
+    map(alt((tag(&[0u8][..]), tag(&[1u8][..]))), |b: &[u8]| b[0] != 0)(i)
+}
+
+pub fn binary_i64(i: &[u8]) -> IResult<&[u8], i64> {
+    preceded(sized(8), nom::number::complete::le_i64)(i)
+}
+
+pub fn binary_u8(i: &[u8]) -> IResult<&[u8], u8> {
+    preceded(sized(1), nom::number::complete::le_u8)(i)
+}
+
+/// A scalar's 1-byte sizeof marker is ambiguous between `f32`/`i32` (both 4
+/// bytes) and between `f64`/`i64` (both 8 bytes), so the caller's expected
+/// `dtype` resolves which one to read.
+pub fn binary_scalar(dtype: DatumType, i: &[u8]) -> IResult<&[u8], Tensor> {
+    match dtype {
+        DatumType::F64 => map(binary_f64, Tensor::from)(i),
+        DatumType::I32 => map(binary_i32, Tensor::from)(i),
+        DatumType::I64 => map(binary_i64, Tensor::from)(i),
+        DatumType::U8 => map(binary_u8, Tensor::from)(i),
+        DatumType::Bool => map(binary_bool, Tensor::from)(i),
+        _ => map(binary_f32, Tensor::from)(i),
+    }
+}
+
+/// A nom failure carrying no payload beyond "this input didn't verify",
+/// used for the malformed-dimension and dtype-mismatch cases below; `nnet3`
+/// turns it into a `TractError` same as any other parse failure, rather than
+/// panicking.
+fn verify_failure(i: &[u8]) -> nom::Err<nom::error::Error<&[u8]>> {
+    nom::Err::Failure(nom::error::Error::new(i, nom::error::ErrorKind::Verify))
+}
+
+/// Validates `rows`/`cols` (as read off the wire) are non-negative and that
+/// their product doesn't overflow, before it's used to size a read or an
+/// `ndarray` shape.
+fn checked_dims(i: &[u8], rows: i32, cols: i32) -> IResult<&[u8], (usize, usize)> {
+    let dims = if rows >= 0 && cols >= 0 {
+        (rows as usize).checked_mul(cols as usize).map(|_| (rows as usize, cols as usize))
+    } else {
+        None
+    };
+    Ok((i, dims.ok_or_else(|| verify_failure(i))?))
+}
+
+/// Validates the vector/matrix's on-disk element-width tag (`FV`/`DV`/`FM`/
+/// `DM`, surfaced as `f32`/`f64`) against the attribute's expected `dtype`,
+/// rather than silently trusting whatever width the file declares.
+fn check_dtype(i: &[u8], found: DatumType, expected: DatumType) -> IResult<&[u8], ()> {
+    if found == expected {
+        Ok((i, ()))
+    } else {
+        Err(verify_failure(i))
+    }
+}
+
+pub fn binary_vector(dtype: DatumType, i: &[u8]) -> IResult<&[u8], Tensor> {
+    let (rest, (found, t)) = alt((
+        map(binary_vector_f32, |t| (DatumType::F32, t)),
+        map(binary_vector_f64, |t| (DatumType::F64, t)),
+    ))(i)?;
+    let (rest, ()) = check_dtype(rest, found, dtype)?;
+    Ok((rest, t))
+}
+
+fn binary_vector_f32(i: &[u8]) -> IResult<&[u8], Tensor> {
+    let (i, _) = tag("FV")(i)?;
+    let (i, dim) = binary_i32(i)?;
+    let (i, (_, dim)) = checked_dims(i, 1, dim)?;
+    let (i, data) = count(le_f32, dim)(i)?;
+    Ok((i, tensor1(&data)))
+}
+
+fn binary_vector_f64(i: &[u8]) -> IResult<&[u8], Tensor> {
+    let (i, _) = tag("DV")(i)?;
+    let (i, dim) = binary_i32(i)?;
+    let (i, (_, dim)) = checked_dims(i, 1, dim)?;
+    let (i, data) = count(le_f64, dim)(i)?;
+    Ok((i, tensor1(&data)))
+}
+
+pub fn binary_matrix(dtype: DatumType, i: &[u8]) -> IResult<&[u8], Tensor> {
+    let (rest, (found, t)) = alt((
+        map(binary_matrix_f32, |t| (DatumType::F32, t)),
+        map(binary_matrix_f64, |t| (DatumType::F64, t)),
+    ))(i)?;
+    let (rest, ()) = check_dtype(rest, found, dtype)?;
+    Ok((rest, t))
+}
+
+fn binary_matrix_f32(i: &[u8]) -> IResult<&[u8], Tensor> {
+    let (i, _) = tag("FM")(i)?;
+    let (i, rows) = binary_i32(i)?;
+    let (i, cols) = binary_i32(i)?;
+    let (i, (rows, cols)) = checked_dims(i, rows, cols)?;
+    let (i, data) = count(le_f32, rows * cols)(i)?;
+    let t = tract_core::ndarray::Array1::from_vec(data).into_shape((rows, cols)).unwrap();
+    Ok((i, t.into_tensor()))
+}
+
+fn binary_matrix_f64(i: &[u8]) -> IResult<&[u8], Tensor> {
+    let (i, _) = tag("DM")(i)?;
+    let (i, rows) = binary_i32(i)?;
+    let (i, cols) = binary_i32(i)?;
+    let (i, (rows, cols)) = checked_dims(i, rows, cols)?;
+    let (i, data) = count(le_f64, rows * cols)(i)?;
+    let t = tract_core::ndarray::Array1::from_vec(data).into_shape((rows, cols)).unwrap();
+    Ok((i, t.into_tensor()))
+}
+
+pub fn binary_tensor(dtype: DatumType, i: &[u8]) -> IResult<&[u8], Tensor> {
+    alt((
+        move |i| binary_scalar(dtype, i),
+        move |i| binary_vector(dtype, i),
+        move |i| binary_matrix(dtype, i),
+    ))(i)
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_scalar_f32() {
+        let mut bytes = vec![4u8];
+        bytes.extend_from_slice(&1.5f32.to_le_bytes());
+        assert_eq!(binary_scalar(DatumType::F32, &bytes).unwrap().1, Tensor::from(1.5f32));
+    }
+
+    #[test]
+    fn test_binary_scalar_i32() {
+        let mut bytes = vec![4u8];
+        bytes.extend_from_slice(&42i32.to_le_bytes());
+        assert_eq!(binary_scalar(DatumType::I32, &bytes).unwrap().1, Tensor::from(42i32));
+    }
+
+    #[test]
+    fn test_binary_vector() {
+        let mut bytes = b"FV".to_vec();
+        bytes.push(4u8);
+        bytes.extend_from_slice(&2i32.to_le_bytes());
+        bytes.extend_from_slice(&7.0f32.to_le_bytes());
+        bytes.extend_from_slice(&8.0f32.to_le_bytes());
+        assert_eq!(binary_vector(DatumType::F32, &bytes).unwrap().1, tensor1(&[7.0f32, 8.0]));
+    }
+
+    #[test]
+    fn test_binary_vector_dtype_mismatch_is_an_error() {
+        let mut bytes = b"FV".to_vec();
+        bytes.push(4u8);
+        bytes.extend_from_slice(&2i32.to_le_bytes());
+        bytes.extend_from_slice(&7.0f32.to_le_bytes());
+        bytes.extend_from_slice(&8.0f32.to_le_bytes());
+        assert!(binary_vector(DatumType::F64, &bytes).is_err());
+    }
+
+    #[test]
+    fn test_binary_matrix() {
+        let mut bytes = b"FM".to_vec();
+        bytes.push(4u8);
+        bytes.extend_from_slice(&2i32.to_le_bytes());
+        bytes.push(4u8);
+        bytes.extend_from_slice(&3i32.to_le_bytes());
+        for v in &[1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        assert_eq!(
+            binary_matrix(DatumType::F32, &bytes).unwrap().1,
+            tensor2(&[[1.0f32, 2.0, 3.0], [4.0, 5.0, 6.0]])
+        );
+    }
+
+    #[test]
+    fn test_binary_matrix_dtype_mismatch_is_an_error() {
+        let mut bytes = b"FM".to_vec();
+        bytes.push(4u8);
+        bytes.extend_from_slice(&2i32.to_le_bytes());
+        bytes.push(4u8);
+        bytes.extend_from_slice(&3i32.to_le_bytes());
+        for v in &[1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        assert!(binary_matrix(DatumType::F64, &bytes).is_err());
+    }
+
+    #[test]
+    fn test_binary_matrix_overflowing_dims_is_an_error_not_a_panic() {
+        let mut bytes = b"FM".to_vec();
+        bytes.push(4u8);
+        bytes.extend_from_slice(&i32::MAX.to_le_bytes());
+        bytes.push(4u8);
+        bytes.extend_from_slice(&i32::MAX.to_le_bytes());
+        assert!(binary_matrix(DatumType::F32, &bytes).is_err());
+    }
+
+    #[test]
+    fn test_binary_matrix_negative_dims_is_an_error_not_a_panic() {
+        let mut bytes = b"FM".to_vec();
+        bytes.push(4u8);
+        bytes.extend_from_slice(&(-1i32).to_le_bytes());
+        bytes.push(4u8);
+        bytes.extend_from_slice(&3i32.to_le_bytes());
+        assert!(binary_matrix(DatumType::F32, &bytes).is_err());
+    }
+}